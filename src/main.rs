@@ -1,5 +1,6 @@
 use anyhow::{anyhow, Result};
 use clap::{Parser, Subcommand};
+use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
@@ -14,12 +15,40 @@ struct PackageInfo {
     flake_url: String,
     install_date: String,
     flake_lock: Option<String>,
+    /// The `nix profile` generation this entry was installed/pinned under, so
+    /// it can be rolled back to its exact store paths later.
+    #[serde(default)]
+    profile_generation: Option<u64>,
+}
+
+// How long a resolved version cache entry is trusted before we re-resolve
+// it against the channels. A failed lookup falls back to `nixpkgs#<pkg>`,
+// so without a TTL that fallback would be cached as the answer forever,
+// masking the real version once the channel catches up.
+const VERSION_CACHE_TTL_SECS: i64 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CachedVersion {
+    flake_url: String,
+    cached_at: String,
+}
+
+impl CachedVersion {
+    fn is_stale(&self) -> bool {
+        match chrono::DateTime::parse_from_rfc3339(&self.cached_at) {
+            Ok(cached_at) => {
+                let age = chrono::Utc::now().signed_duration_since(cached_at);
+                age >= chrono::Duration::seconds(VERSION_CACHE_TTL_SECS)
+            }
+            Err(_) => true,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct PackageRegistry {
     packages: HashMap<String, Vec<PackageInfo>>,
-    version_cache: HashMap<String, HashMap<String, String>>, // package -> version -> flake_url
+    version_cache: HashMap<String, HashMap<String, CachedVersion>>, // package -> version -> cached resolution
 }
 
 impl PackageRegistry {
@@ -51,7 +80,7 @@ impl PackageRegistry {
     }
 
     fn add_package(&mut self, package: PackageInfo) {
-        self.packages.entry(package.name.clone()).or_insert_with(Vec::new).push(package);
+        self.packages.entry(package.name.clone()).or_default().push(package);
     }
 
     fn get_package_history(&self, package: &str) -> Option<&Vec<PackageInfo>> {
@@ -61,14 +90,24 @@ impl PackageRegistry {
     fn cache_version(&mut self, package: &str, version: &str, flake_url: &str) {
         self.version_cache
             .entry(package.to_string())
-            .or_insert_with(HashMap::new)
-            .insert(version.to_string(), flake_url.to_string());
+            .or_default()
+            .insert(
+                version.to_string(),
+                CachedVersion {
+                    flake_url: flake_url.to_string(),
+                    cached_at: chrono::Utc::now().to_rfc3339(),
+                },
+            );
     }
 
+    // Returns the cached resolution only if it hasn't gone stale, so a
+    // previously-failed lookup doesn't permanently mask the real version.
     fn get_cached_version(&self, package: &str, version: &str) -> Option<&String> {
         self.version_cache
             .get(package)
             .and_then(|versions| versions.get(version))
+            .filter(|cached| !cached.is_stale())
+            .map(|cached| &cached.flake_url)
     }
 }
 
@@ -77,6 +116,343 @@ fn get_registry_path() -> Result<PathBuf> {
     Ok(home_dir.join(".nixbrew").join("registry.json"))
 }
 
+// Local SQLite package index, queried by `Search` instead of shelling out.
+#[derive(Debug, Clone)]
+struct PackageIndexEntry {
+    attr: String,
+    pname: String,
+    version: String,
+    description: String,
+    channel: String,
+}
+
+fn get_index_db_path() -> Result<PathBuf> {
+    let home_dir = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
+    Ok(home_dir.join(".nixbrew").join("index.sqlite"))
+}
+
+fn open_index_db() -> Result<Connection> {
+    let path = get_index_db_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let conn = Connection::open(path)?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS packages (
+            attr        TEXT NOT NULL,
+            pname       TEXT NOT NULL,
+            version     TEXT NOT NULL,
+            description TEXT NOT NULL,
+            channel     TEXT NOT NULL,
+            PRIMARY KEY (attr, channel)
+        );
+        CREATE TABLE IF NOT EXISTS index_metadata (
+            channel      TEXT PRIMARY KEY,
+            last_refresh TEXT NOT NULL
+        );",
+    )?;
+    Ok(conn)
+}
+
+// Builds (or refreshes) the local package index for a channel by running
+// `nix search` once and bulk-inserting the results.
+async fn build_package_index(channel: &str) -> Result<()> {
+    println!("Indexing {}...", channel);
+
+    let flake_ref = format!("nixpkgs/{}", channel);
+    let output = Command::new("nix")
+        .args([
+            "--extra-experimental-features",
+            "nix-command",
+            "--extra-experimental-features",
+            "flakes",
+            "search",
+            &flake_ref,
+            "^",
+            "--json",
+        ])
+        .env("NIXPKGS_ALLOW_UNFREE", "1")
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "'nix search {}' failed while building the index",
+            flake_ref
+        ));
+    }
+
+    let raw = String::from_utf8(output.stdout)?;
+    let parsed: serde_json::Value = serde_json::from_str(&raw)?;
+    let entries = parsed
+        .as_object()
+        .ok_or_else(|| anyhow!("Unexpected 'nix search --json' output"))?;
+
+    let conn = open_index_db()?;
+    conn.execute("DELETE FROM packages WHERE channel = ?1", [channel])?;
+
+    for (key, value) in entries {
+        // Keys look like "legacyPackages.x86_64-linux.python311Packages.numpy";
+        // strip only the per-system prefix so nested attrs keep their full path.
+        let attr = key
+            .split_once('.')
+            .and_then(|(_, rest)| rest.split_once('.'))
+            .map(|(_, attr_path)| attr_path)
+            .unwrap_or(key)
+            .to_string();
+        let pname = value
+            .get("pname")
+            .and_then(|v| v.as_str())
+            .unwrap_or(&attr)
+            .to_string();
+        let version = value
+            .get("version")
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string();
+        let description = value
+            .get("description")
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string();
+
+        conn.execute(
+            "INSERT INTO packages (attr, pname, version, description, channel)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(attr, channel) DO UPDATE SET
+                pname = excluded.pname,
+                version = excluded.version,
+                description = excluded.description",
+            rusqlite::params![attr, pname, version, description, channel],
+        )?;
+    }
+
+    conn.execute(
+        "INSERT INTO index_metadata (channel, last_refresh) VALUES (?1, ?2)
+         ON CONFLICT(channel) DO UPDATE SET last_refresh = excluded.last_refresh",
+        rusqlite::params![channel, chrono::Utc::now().to_rfc3339()],
+    )?;
+
+    println!("Indexed {} packages for {}", entries.len(), channel);
+    Ok(())
+}
+
+// Queries the local index, ranking exact `pname` matches above substring
+// matches elsewhere, and deduping by attr across channels.
+fn search_index(query: &str) -> Result<Vec<PackageIndexEntry>> {
+    let conn = open_index_db()?;
+    let like_pattern = format!("%{}%", query);
+
+    // `GROUP BY attr` alone picks an arbitrary row per group; rank within
+    // each attr by the same "exact pname match" preference used for the
+    // overall ordering so the deduped winner is actually the best match.
+    let mut stmt = conn.prepare(
+        "WITH ranked AS (
+             SELECT attr, pname, version, description, channel,
+                    ROW_NUMBER() OVER (
+                        PARTITION BY attr
+                        ORDER BY (pname = ?2) DESC, channel ASC
+                    ) AS rn
+             FROM packages
+             WHERE attr LIKE ?1 OR pname LIKE ?1 OR description LIKE ?1
+         )
+         SELECT attr, pname, version, description, channel
+         FROM ranked
+         WHERE rn = 1
+         ORDER BY (pname = ?2) DESC, pname ASC
+         LIMIT 50",
+    )?;
+
+    let rows = stmt.query_map(rusqlite::params![like_pattern, query], |row| {
+        Ok(PackageIndexEntry {
+            attr: row.get(0)?,
+            pname: row.get(1)?,
+            version: row.get(2)?,
+            description: row.get(3)?,
+            channel: row.get(4)?,
+        })
+    })?;
+
+    let mut entries = Vec::new();
+    for row in rows {
+        entries.push(row?);
+    }
+    Ok(entries)
+}
+
+// Maps program/binary names to the package attribute that provides them,
+// via the channel's programs.sqlite (cached under ~/.nixbrew/).
+const NIXBREW_SYSTEM: &str = "x86_64-linux";
+
+fn get_programs_db_path(channel: &str) -> Result<PathBuf> {
+    let home_dir = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
+    Ok(home_dir
+        .join(".nixbrew")
+        .join(format!("programs-{}.sqlite", channel)))
+}
+
+// Downloads the channel's programs.sqlite if we don't already have a cached
+// copy, and returns the local path.
+async fn ensure_programs_db(channel: &str) -> Result<PathBuf> {
+    let path = get_programs_db_path(channel)?;
+    if path.exists() {
+        return Ok(path);
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let url = format!("https://channels.nixos.org/{}/programs.sqlite", channel);
+    println!("Fetching program index for {} from {}...", channel, url);
+
+    let status = Command::new("curl")
+        .args(["-L", "-f", "-o"])
+        .arg(&path)
+        .arg(&url)
+        .status()
+        .await?;
+
+    if !status.success() {
+        return Err(anyhow!(
+            "Failed to download programs.sqlite for channel '{}'",
+            channel
+        ));
+    }
+
+    Ok(path)
+}
+
+// Looks up the package attribute(s) that provide the given program name.
+fn query_programs_db(db_path: &PathBuf, program: &str) -> Result<Vec<String>> {
+    let conn = Connection::open_with_flags(db_path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT package FROM Programs WHERE program = ?1 AND system = ?2 ORDER BY package",
+    )?;
+
+    let rows = stmt.query_map(rusqlite::params![program, NIXBREW_SYSTEM], |row| {
+        row.get::<_, String>(0)
+    })?;
+
+    let mut packages = Vec::new();
+    for row in rows {
+        packages.push(row?);
+    }
+    Ok(packages)
+}
+
+async fn provides_command(program: &str, channel: &str, install: bool) -> Result<()> {
+    let db_path = ensure_programs_db(channel).await?;
+    let packages = query_programs_db(&db_path, program)?;
+
+    if packages.is_empty() {
+        println!("No package in {} provides '{}'", channel, program);
+        return Ok(());
+    }
+
+    println!("'{}' is provided by:", program);
+    for package in &packages {
+        println!("  {}", package);
+    }
+
+    if install {
+        let package = &packages[0];
+        println!("Installing {}...", package);
+        let flake_url = build_flake_url(package, None).await?;
+        run_nix_command(vec!["profile", "add", &flake_url]).await?;
+    }
+
+    Ok(())
+}
+
+// One element of a `nix profile list --json` manifest.
+#[derive(Deserialize, Debug, Clone)]
+struct ProfileElement {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(rename = "attrPath", default)]
+    attr_path: Option<String>,
+    #[serde(rename = "originalUrl", default)]
+    original_url: Option<String>,
+    #[serde(rename = "storePaths", default)]
+    store_paths: Vec<String>,
+}
+
+impl ProfileElement {
+    /// Best-effort human-readable label: the element's own `name` if recent
+    /// Nix provided one, else its attribute path.
+    fn display_name(&self) -> &str {
+        self.name
+            .as_deref()
+            .or(self.attr_path.as_deref())
+            .unwrap_or("<unknown>")
+    }
+
+    /// True if this element looks like it installs `package`, by name,
+    /// attrPath, or the flake URL it was added from.
+    fn matches_package(&self, package: &str) -> bool {
+        self.display_name() == package
+            || self.attr_path.as_deref() == Some(package)
+            || self
+                .original_url
+                .as_deref()
+                .map(|url| url == package || url.ends_with(&format!("#{}", package)))
+                .unwrap_or(false)
+    }
+}
+
+// Parses `nix profile list --json` into structured elements, tolerating both
+// the object-keyed-by-index and array manifest shapes Nix has shipped.
+async fn list_profile_elements() -> Result<Vec<ProfileElement>> {
+    let output = Command::new("nix")
+        .args([
+            "--extra-experimental-features",
+            "nix-command",
+            "--extra-experimental-features",
+            "flakes",
+            "profile",
+            "list",
+            "--json",
+        ])
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(anyhow!("Failed to run 'nix profile list --json'"));
+    }
+
+    let raw = String::from_utf8(output.stdout)?;
+    let manifest: serde_json::Value = serde_json::from_str(&raw)?;
+    let elements_value = manifest
+        .get("elements")
+        .ok_or_else(|| anyhow!("Unexpected 'nix profile list --json' output: missing 'elements'"))?;
+
+    let mut elements = Vec::new();
+    match elements_value {
+        serde_json::Value::Object(map) => {
+            for value in map.values() {
+                elements.push(serde_json::from_value(value.clone())?);
+            }
+        }
+        serde_json::Value::Array(list) => {
+            for value in list {
+                elements.push(serde_json::from_value(value.clone())?);
+            }
+        }
+        other => return Err(anyhow!("Unexpected 'elements' shape: {}", other)),
+    }
+
+    Ok(elements)
+}
+
+fn find_profile_element<'a>(
+    elements: &'a [ProfileElement],
+    package: &str,
+) -> Option<&'a ProfileElement> {
+    elements.iter().find(|e| e.matches_package(package))
+}
+
 async fn create_package_flake(package: &str, version: Option<&str>) -> Result<()> {
     let _flake_url = build_flake_url(package, version).await?;
     let flake_content = format!(
@@ -155,29 +531,30 @@ async fn show_package_history(package: &str) -> Result<()> {
             }
         }
     }
-    
+
+    // Reconcile the registry against what is actually installed right now.
+    match list_profile_elements().await {
+        Ok(elements) => match find_profile_element(&elements, package) {
+            Some(element) => println!(
+                "\nCurrently installed as '{}' ({})",
+                element.display_name(),
+                element.original_url.as_deref().unwrap_or("unknown source")
+            ),
+            None => println!("\nNot currently installed"),
+        },
+        Err(e) => println!("\n(Could not check installed state: {})", e),
+    }
+
     Ok(())
 }
 
 async fn rollback_package(package: &str, version: &str) -> Result<()> {
     println!("Rolling back {} to version {}...", package, version);
-    
-    // First uninstall current version
-    let list_output = Command::new("nix")
-        .args(["profile", "list"])
-        .output()
-        .await?;
 
-    if list_output.status.success() {
-        let list_str = String::from_utf8(list_output.stdout)?;
-        for line in list_str.lines() {
-            if line.contains(&format!("nixpkgs#{}", package)) {
-                if let Some(index) = line.split_whitespace().next() {
-                    run_nix_command(vec!["profile", "remove", index]).await?;
-                    break;
-                }
-            }
-        }
+    // First uninstall current version
+    let elements = list_profile_elements().await?;
+    if let Some(element) = find_profile_element(&elements, package) {
+        run_nix_command(vec!["profile", "remove", element.display_name()]).await?;
     }
 
     // Install the specific version
@@ -192,14 +569,345 @@ async fn rollback_package(package: &str, version: &str) -> Result<()> {
         flake_url,
         install_date: chrono::Utc::now().to_rfc3339(),
         flake_lock: None,
+        profile_generation: current_profile_generation().ok().flatten(),
     };
     registry.add_package(package_info);
     registry.save()?;
-    
+
     println!("Successfully rolled back {} to version {}", package, version);
     Ok(())
 }
 
+// One `nix profile` generation, read from the generation symlinks directly;
+// used to list generations and to record the current one at install/pin time.
+#[derive(Debug, Clone)]
+struct ProfileGenerationInfo {
+    number: u64,
+    timestamp: String,
+    current: bool,
+}
+
+fn get_profile_link_path() -> Result<PathBuf> {
+    let home_dir = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
+    Ok(home_dir.join(".local/state/nix/profiles/profile"))
+}
+
+// Reads the `profile-<N>-link` symlinks next to the profile link and
+// determines which one the profile currently points at.
+fn list_generations() -> Result<Vec<ProfileGenerationInfo>> {
+    let profile_link = get_profile_link_path()?;
+    let profile_dir = profile_link
+        .parent()
+        .ok_or_else(|| anyhow!("Could not determine profile directory"))?;
+    let current_link = fs::read_link(&profile_link).ok();
+
+    let mut generations = Vec::new();
+    for entry in fs::read_dir(profile_dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let name = file_name.to_string_lossy();
+        let Some(number_str) = name
+            .strip_prefix("profile-")
+            .and_then(|s| s.strip_suffix("-link"))
+        else {
+            continue;
+        };
+        let Ok(number) = number_str.parse::<u64>() else {
+            continue;
+        };
+
+        let modified = entry.metadata()?.modified()?;
+        let timestamp = chrono::DateTime::<chrono::Utc>::from(modified).to_rfc3339();
+        let current = current_link
+            .as_ref()
+            .and_then(|link| link.file_name())
+            .map(|n| n == file_name.as_os_str())
+            .unwrap_or(false);
+
+        generations.push(ProfileGenerationInfo {
+            number,
+            timestamp,
+            current,
+        });
+    }
+
+    generations.sort_by_key(|g| g.number);
+    Ok(generations)
+}
+
+fn current_profile_generation() -> Result<Option<u64>> {
+    Ok(list_generations()?.into_iter().find(|g| g.current).map(|g| g.number))
+}
+
+// Reads the element names a given generation's manifest declares, so
+// `generations` can show what each generation added/removed.
+fn read_generation_element_names(generation: u64) -> Result<Vec<String>> {
+    let profile_link = get_profile_link_path()?;
+    let profile_dir = profile_link
+        .parent()
+        .ok_or_else(|| anyhow!("Could not determine profile directory"))?;
+    let manifest_path = profile_dir
+        .join(format!("profile-{}-link", generation))
+        .join("manifest.json");
+
+    if !manifest_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(manifest_path)?;
+    let manifest: serde_json::Value = serde_json::from_str(&content)?;
+    let Some(elements_value) = manifest.get("elements") else {
+        return Ok(Vec::new());
+    };
+
+    let values: Vec<serde_json::Value> = match elements_value {
+        serde_json::Value::Object(map) => map.values().cloned().collect(),
+        serde_json::Value::Array(list) => list.clone(),
+        _ => Vec::new(),
+    };
+
+    let mut names = Vec::new();
+    for value in values {
+        let element: ProfileElement = serde_json::from_value(value)?;
+        names.push(element.display_name().to_string());
+    }
+    Ok(names)
+}
+
+async fn generations_command() -> Result<()> {
+    let generations = list_generations()?;
+    if generations.is_empty() {
+        println!("No generations found.");
+        return Ok(());
+    }
+
+    let mut previous: Vec<String> = Vec::new();
+    for generation in &generations {
+        let names = read_generation_element_names(generation.number).unwrap_or_default();
+        let added: Vec<&String> = names.iter().filter(|n| !previous.contains(n)).collect();
+        let removed: Vec<&String> = previous.iter().filter(|n| !names.contains(n)).collect();
+
+        println!(
+            "Generation {}{} - {}",
+            generation.number,
+            if generation.current { " (current)" } else { "" },
+            generation.timestamp
+        );
+        if !added.is_empty() {
+            println!(
+                "  + {}",
+                added.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(", ")
+            );
+        }
+        if !removed.is_empty() {
+            println!(
+                "  - {}",
+                removed.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(", ")
+            );
+        }
+
+        previous = names;
+    }
+
+    Ok(())
+}
+
+async fn rollback_to_generation(package: &str, generation: u64) -> Result<()> {
+    println!("Switching profile to generation {}...", generation);
+    run_nix_command(vec!["profile", "rollback", "--to", &generation.to_string()]).await?;
+
+    let elements = list_profile_elements().await?;
+    if let Some(element) = find_profile_element(&elements, package) {
+        let mut registry = PackageRegistry::load()?;
+        let package_info = PackageInfo {
+            name: package.to_string(),
+            version: element.display_name().to_string(),
+            flake_url: element.original_url.clone().unwrap_or_default(),
+            install_date: chrono::Utc::now().to_rfc3339(),
+            flake_lock: None,
+            profile_generation: Some(generation),
+        };
+        registry.add_package(package_info);
+        registry.save()?;
+    }
+
+    println!("Profile now at generation {}", generation);
+    Ok(())
+}
+
+// Clears the resolved-version cache and drops the local search index so the
+// next `search`/install resolves against fresh data.
+async fn clean_cache() -> Result<()> {
+    let mut registry = PackageRegistry::load()?;
+    registry.version_cache.clear();
+    registry.save()?;
+    println!("Cleared version cache.");
+
+    let index_path = get_index_db_path()?;
+    if index_path.exists() {
+        fs::remove_file(&index_path)?;
+        println!("Cleared search index.");
+    }
+
+    Ok(())
+}
+
+async fn clean_store() -> Result<()> {
+    println!("Wiping profile history...");
+    run_nix_command(vec!["profile", "wipe-history"]).await?;
+    println!("Running nix store garbage collection...");
+    run_nix_command(vec!["store", "gc"]).await
+}
+
+// Maintains a single flake under ~/.nixbrew/flakes/profile listing every
+// installed package, realized atomically by `apply`.
+fn get_declarative_flake_dir() -> Result<PathBuf> {
+    let home_dir = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
+    Ok(home_dir.join(".nixbrew").join("flakes").join("profile"))
+}
+
+fn get_declared_packages_path() -> Result<PathBuf> {
+    Ok(get_declarative_flake_dir()?.join("packages.json"))
+}
+
+fn load_declared_packages() -> Result<Vec<String>> {
+    let path = get_declared_packages_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+fn save_declared_packages(packages: &[String]) -> Result<()> {
+    let dir = get_declarative_flake_dir()?;
+    fs::create_dir_all(&dir)?;
+    let content = serde_json::to_string_pretty(packages)?;
+    fs::write(get_declared_packages_path()?, content)?;
+    Ok(())
+}
+
+// Regenerates flake.nix from the declared package list and refreshes
+// flake.lock, mirroring `create_package_flake`'s single-package version.
+async fn regenerate_declarative_flake(packages: &[String]) -> Result<()> {
+    let dir = get_declarative_flake_dir()?;
+    fs::create_dir_all(&dir)?;
+
+    let per_package_outputs: String = packages
+        .iter()
+        .map(|p| format!("      packages.${{system}}.{0} = pkgs.{0};\n", p))
+        .collect();
+    let buildenv_paths: String = packages
+        .iter()
+        .map(|p| format!("pkgs.{}", p))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let flake_content = format!(
+        r#"{{
+  description = "Declarative nixbrew profile";
+
+  inputs = {{
+    nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  }};
+
+  outputs = {{ self, nixpkgs }}:
+    let
+      system = "x86_64-linux";
+      pkgs = nixpkgs.legacyPackages.${{system}};
+    in {{
+{0}      packages.${{system}}.default = pkgs.buildEnv {{
+        name = "nixbrew-profile";
+        paths = [ {1} ];
+      }};
+    }};
+}}
+"#,
+        per_package_outputs, buildenv_paths
+    );
+
+    fs::write(dir.join("flake.nix"), flake_content)?;
+    run_nix_command(vec!["flake", "update", "--flake", &dir.to_string_lossy()]).await
+}
+
+// Adds `package` to the declarative profile manifest and regenerates the flake.
+async fn add_declared_package(package: &str) -> Result<()> {
+    let mut packages = load_declared_packages()?;
+    if !packages.iter().any(|p| p == package) {
+        packages.push(package.to_string());
+        packages.sort();
+        save_declared_packages(&packages)?;
+    }
+    regenerate_declarative_flake(&packages).await
+}
+
+// Removes `package` from the declarative profile manifest and regenerates the flake.
+async fn remove_declared_package(package: &str) -> Result<()> {
+    let mut packages = load_declared_packages()?;
+    packages.retain(|p| p != package);
+    save_declared_packages(&packages)?;
+    regenerate_declarative_flake(&packages).await
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct NixbrewConfig {
+    #[serde(default)]
+    rebuild_command: Option<String>,
+}
+
+fn get_config_path() -> Result<PathBuf> {
+    let home_dir = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
+    Ok(home_dir.join(".nixbrew").join("config.json"))
+}
+
+fn load_config() -> Result<NixbrewConfig> {
+    let path = get_config_path()?;
+    if !path.exists() {
+        return Ok(NixbrewConfig::default());
+    }
+    let content = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+async fn run_configured_rebuild_command() -> Result<()> {
+    let config = load_config()?;
+    match config.rebuild_command {
+        Some(cmd) => {
+            println!("Running configured rebuild command: {}", cmd);
+            let status = Command::new("sh").arg("-c").arg(&cmd).status().await?;
+            if !status.success() {
+                return Err(anyhow!(
+                    "Rebuild command failed with exit code {}",
+                    status.code().unwrap_or(-1)
+                ));
+            }
+            Ok(())
+        }
+        None => Err(anyhow!(
+            "No rebuild command configured; set \"rebuild_command\" in ~/.nixbrew/config.json"
+        )),
+    }
+}
+
+// Realizes the whole declared package set atomically via the profile flake.
+async fn apply_command(rebuild: bool) -> Result<()> {
+    let dir = get_declarative_flake_dir()?;
+    if !get_declared_packages_path()?.exists() {
+        return Err(anyhow!(
+            "No declarative profile yet; install/pin a package first."
+        ));
+    }
+
+    println!("Applying declarative profile from {}...", dir.display());
+    run_nix_command(vec!["profile", "add", &dir.to_string_lossy()]).await?;
+
+    if rebuild {
+        run_configured_rebuild_command().await?;
+    }
+
+    Ok(())
+}
+
 // Define the structure of our command-line interface using Clap's derive macros.
 #[derive(Parser)]
 #[command(name = "nixbrew")]
@@ -229,6 +937,23 @@ enum Commands {
         /// The search query
         query: String,
     },
+    /// Build or refresh the local package search index
+    Index {
+        /// The nixpkgs channel to index (e.g. "nixos-unstable", "nixos-23.11")
+        #[arg(long, default_value = "nixos-unstable")]
+        channel: String,
+    },
+    /// Find which package provides a given command/binary
+    Provides {
+        /// The command/binary name to look up (e.g. "make")
+        command: String,
+        /// The nixpkgs channel to look up against
+        #[arg(long, default_value = "nixos-unstable")]
+        channel: String,
+        /// Install the first matching package
+        #[arg(long)]
+        install: bool,
+    },
     /// List installed packages
     List,
     /// Update the nixpkgs flake (like 'brew update')
@@ -262,13 +987,37 @@ enum Commands {
         /// The name of the package
         package: String,
     },
-    /// Rollback to a previous version
+    /// Rollback to a previous version, or to a specific profile generation
     Rollback {
         /// The name of the package
         package: String,
         /// The version to rollback to
-        version: String,
+        version: Option<String>,
+        /// Switch the whole profile to this generation instead of reinstalling a version
+        #[arg(long)]
+        to_generation: Option<u64>,
+    },
+    /// List profile generations with timestamps and what each added/removed
+    Generations,
+    /// Clear caches or run nix garbage collection
+    Clean {
+        #[command(subcommand)]
+        target: CleanTarget,
     },
+    /// Realize the declarative profile flake, installing every declared package atomically
+    Apply {
+        /// Also run the configured rebuild command after applying
+        #[arg(long)]
+        rebuild: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum CleanTarget {
+    /// Clear the resolved-version cache and the local search index
+    Cache,
+    /// Wipe profile history and run `nix store gc` to reclaim space
+    Store,
 }
 
 // Helper function to run a `nix` command and pipe its output to the console.
@@ -303,44 +1052,76 @@ async fn handle_command(cmd: Commands) -> Result<()> {
         Commands::Install { package, version } => {
           println!("Installing {}{}...", package, version.as_ref().map(|v| format!(" version {}", v)).unwrap_or_default());
           let flake_url = build_flake_url(&package, version.as_deref()).await?;
-          run_nix_command(vec!["profile", "add", &flake_url]).await
+          run_nix_command(vec!["profile", "add", &flake_url]).await?;
+
+          // Record it in the registry, same as Pin, so install-time state
+          // (including the profile generation) isn't lost.
+          let mut registry = PackageRegistry::load()?;
+          let package_info = PackageInfo {
+              name: package.clone(),
+              version: version.clone().unwrap_or_else(|| "latest".to_string()),
+              flake_url,
+              install_date: chrono::Utc::now().to_rfc3339(),
+              flake_lock: None,
+              profile_generation: current_profile_generation().ok().flatten(),
+          };
+          registry.add_package(package_info);
+          registry.save()?;
+
+          add_declared_package(&package).await
         }
         Commands::Uninstall { package } => {
-            // Find the package's index in the profile.
             println!("Finding package '{}' to uninstall...", package);
-            let list_output = Command::new("nix")
-                .args(["profile", "list"])
-                .output()
-                .await?;
+            let elements = list_profile_elements().await?;
 
-            if !list_output.status.success() {
-                return Err(anyhow!("Failed to run 'nix profile list'"));
-            }
-
-            let list_str = String::from_utf8(list_output.stdout)?;
-            let mut pkg_index: Option<String> = None;
-
-            for line in list_str.lines() {
-                // The output looks like: "3  nixpkgs#cowsay-3.04"
-                if line.contains(&format!("nixpkgs#{}", package)) {
-                    pkg_index = line.split_whitespace().next().map(String::from);
-                    break;
-                }
-            }
-
-            match pkg_index {
-                Some(index) => {
-                    println!("Uninstalling {} (index: {})...", package, index);
-                    run_nix_command(vec!["profile", "remove", &index]).await
+            match find_profile_element(&elements, &package) {
+                Some(element) => {
+                    let name = element.display_name().to_string();
+                    println!("Uninstalling {} ('{}')...", package, name);
+                    run_nix_command(vec!["profile", "remove", &name]).await?;
+                    remove_declared_package(&package).await
                 }
                 None => Err(anyhow!("Package '{}' not found in profile.", package)),
             }
         }
         Commands::Search { query } => {
-            run_nix_command(vec!["search", "nixpkgs", &query]).await
+            let entries = search_index(&query)?;
+            if entries.is_empty() {
+                println!("Index empty or no matches, falling back to live 'nix search'...");
+                return run_nix_command(vec!["search", "nixpkgs", &query]).await;
+            }
+
+            for entry in entries {
+                println!(
+                    "* {} ({} {}) [{}]\n  {}",
+                    entry.attr, entry.pname, entry.version, entry.channel, entry.description
+                );
+            }
+            Ok(())
         }
+        Commands::Index { channel } => build_package_index(&channel).await,
+        Commands::Provides {
+            command,
+            channel,
+            install,
+        } => provides_command(&command, &channel, install).await,
         Commands::List => {
-            run_nix_command(vec!["profile", "list"]).await
+            let elements = list_profile_elements().await?;
+            if elements.is_empty() {
+                println!("No packages installed.");
+                return Ok(());
+            }
+            for element in &elements {
+                println!(
+                    "{}  ({})",
+                    element.display_name(),
+                    element.original_url.as_deref().unwrap_or("unknown source")
+                );
+                for store_path in &element.store_paths {
+                    println!("    {}", store_path);
+                }
+            }
+            Ok(())
         }
         Commands::Update => {
             println!("Updating nixpkgs flake...");
@@ -388,10 +1169,11 @@ async fn handle_command(cmd: Commands) -> Result<()> {
                 flake_url,
                 install_date: chrono::Utc::now().to_rfc3339(),
                 flake_lock: None,
+                profile_generation: current_profile_generation().ok().flatten(),
             };
             registry.add_package(package_info);
             registry.save()?;
-            Ok(())
+            add_declared_package(&package).await
         }
         Commands::CreateFlake { package, version } => {
             create_package_flake(&package, version.as_deref()).await
@@ -399,9 +1181,23 @@ async fn handle_command(cmd: Commands) -> Result<()> {
         Commands::History { package } => {
             show_package_history(&package).await
         }
-        Commands::Rollback { package, version } => {
-            rollback_package(&package, &version).await
-        }
+        Commands::Rollback {
+            package,
+            version,
+            to_generation,
+        } => match (to_generation, version) {
+            (Some(generation), _) => rollback_to_generation(&package, generation).await,
+            (None, Some(version)) => rollback_package(&package, &version).await,
+            (None, None) => Err(anyhow!(
+                "Specify a version to rollback to, or --to-generation <N>"
+            )),
+        },
+        Commands::Generations => generations_command().await,
+        Commands::Clean { target } => match target {
+            CleanTarget::Cache => clean_cache().await,
+            CleanTarget::Store => clean_store().await,
+        },
+        Commands::Apply { rebuild } => apply_command(rebuild).await,
     }
 }
 async fn build_flake_url(package: &str, version: Option<&str>) -> Result<String> {